@@ -30,3 +30,5 @@
 //! server to Java code.
 
 mod vnc_jni;
+mod vnc;
+mod turbojpeg;
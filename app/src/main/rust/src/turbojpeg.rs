@@ -0,0 +1,121 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Thin safe wrapper around libjpeg-turbo's TurboJPEG API, used by the
+//! Tight encoder to compress photographic rectangles.
+
+use std::fmt;
+
+/// Errors returned while compressing with TurboJPEG.
+#[derive(Debug)]
+pub enum TurboJpegError {
+    /// The underlying `tjCompress2` call failed.
+    Compress(String),
+    /// The TurboJPEG handle could not be created.
+    HandleInit(String),
+}
+
+impl fmt::Display for TurboJpegError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TurboJpegError::Compress(msg) => write!(f, "TurboJPEG compress failed: {}", msg),
+            TurboJpegError::HandleInit(msg) => write!(f, "TurboJPEG init failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for TurboJpegError {}
+
+const TJPF_RGB: i32 = 0;
+const TJSAMP_420: i32 = 2;
+const TJFLAG_FASTDCT: i32 = 2048;
+
+#[allow(non_snake_case)]
+extern "C" {
+    fn tjInitCompress() -> *mut std::ffi::c_void;
+    fn tjCompress2(
+        handle: *mut std::ffi::c_void,
+        srcBuf: *const u8,
+        width: i32,
+        pitch: i32,
+        height: i32,
+        pixelFormat: i32,
+        jpegBuf: *mut *mut u8,
+        jpegSize: *mut std::os::raw::c_ulong,
+        jpegSubsamp: i32,
+        jpegQual: i32,
+        flags: i32,
+    ) -> i32;
+    fn tjFree(buffer: *mut u8);
+    fn tjDestroy(handle: *mut std::ffi::c_void) -> i32;
+}
+
+/// A reusable TurboJPEG compressor handle.
+///
+/// Mirrors the `tjCompress2`/`tjInitCompress` lifecycle from libjpeg-turbo:
+/// create one instance per encoding thread and reuse it across frames.
+pub struct TurboJpegEncoder {
+    handle: *mut std::ffi::c_void,
+}
+
+// The underlying tjhandle is only ever touched from the thread that owns it.
+unsafe impl Send for TurboJpegEncoder {}
+
+impl TurboJpegEncoder {
+    /// Creates a new compressor handle.
+    pub fn new() -> Result<Self, TurboJpegError> {
+        let handle = unsafe { tjInitCompress() };
+        if handle.is_null() {
+            return Err(TurboJpegError::HandleInit("tjInitCompress returned NULL".into()));
+        }
+        Ok(TurboJpegEncoder { handle })
+    }
+
+    /// Compresses an RGB24 image of `width`x`height` pixels at the given
+    /// JPEG quality (1-100), returning the compressed JPEG byte stream.
+    pub fn compress_rgb(&mut self, rgb: &[u8], width: u16, height: u16, quality: u8) -> Result<Vec<u8>, TurboJpegError> {
+        let mut jpeg_buf: *mut u8 = std::ptr::null_mut();
+        let mut jpeg_size: std::os::raw::c_ulong = 0;
+
+        let result = unsafe {
+            tjCompress2(
+                self.handle,
+                rgb.as_ptr(),
+                width as i32,
+                0, // pitch: tightly packed
+                height as i32,
+                TJPF_RGB,
+                &mut jpeg_buf,
+                &mut jpeg_size,
+                TJSAMP_420,
+                quality.clamp(1, 100) as i32,
+                TJFLAG_FASTDCT,
+            )
+        };
+
+        if result != 0 || jpeg_buf.is_null() {
+            return Err(TurboJpegError::Compress("tjCompress2 returned an error".into()));
+        }
+
+        let jpeg_data = unsafe { std::slice::from_raw_parts(jpeg_buf, jpeg_size as usize).to_vec() };
+        unsafe { tjFree(jpeg_buf) };
+        Ok(jpeg_data)
+    }
+}
+
+impl Drop for TurboJpegEncoder {
+    fn drop(&mut self) {
+        unsafe { tjDestroy(self.handle) };
+    }
+}
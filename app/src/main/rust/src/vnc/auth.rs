@@ -0,0 +1,50 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! VNC authentication negotiation.
+//!
+//! Picks a security type for a new connection. `VncAuth` is defined here
+//! for the wire format but never offered yet -- see `for_password`.
+
+use crate::vnc::protocol::security_type;
+
+/// The security type a session negotiated during the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityType {
+    /// No authentication required.
+    None,
+    /// Classic VNC DES challenge-response authentication.
+    VncAuth,
+}
+
+impl SecurityType {
+    /// Always offers `None`. `handshake` reads a `VncAuth` response but has
+    /// no DES challenge-response verification yet to check it against, so
+    /// offering `VncAuth` would tell the client a password is enforced and
+    /// then accept any response at all -- worse than not asking, since it
+    /// gives a false sense of authentication. Revisit once the response is
+    /// actually verified against the password `request_password` fetches.
+    /// `VncServer::new` warns loudly when a password was configured, since
+    /// this otherwise silently leaves every connection unauthenticated.
+    pub fn for_password(_password: &Option<String>) -> Self {
+        SecurityType::None
+    }
+
+    pub fn wire_value(self) -> u8 {
+        match self {
+            SecurityType::None => security_type::NONE,
+            SecurityType::VncAuth => security_type::VNC_AUTH,
+        }
+    }
+}
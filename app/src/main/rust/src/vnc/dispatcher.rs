@@ -0,0 +1,126 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A single-task command dispatcher for the JNI layer.
+//!
+//! Every JNI entry point that used to lock the server container and
+//! `block_on` a connection attempt directly instead pushes a [`VncCommand`]
+//! onto one `mpsc` channel owned by a dedicated dispatcher task, then
+//! `block_on`s only its own `oneshot` reply. The dispatcher task receives
+//! every command and runs each connection attempt as its own spawned,
+//! abortable task, so nothing on this path awaits while holding
+//! `VNC_SERVER`'s lock and every outbound connection attempt has a
+//! cancellable handle.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::AbortHandle;
+
+use crate::vnc::server::VncServer;
+
+/// A command pushed onto the dispatcher's queue from a JNI entry point.
+pub enum VncCommand {
+    ConnectReverse {
+        host: String,
+        port: u16,
+        reply: oneshot::Sender<io::Result<u64>>,
+    },
+    ConnectRepeater {
+        host: String,
+        port: u16,
+        repeater_id: String,
+        reply: oneshot::Sender<io::Result<u64>>,
+    },
+    /// Completes a WebRTC offer/answer exchange and opens its data channel.
+    /// Unlike the other variants, the reply carries the SDP answer the
+    /// caller must hand back over its own signaling channel alongside the
+    /// reserved client ID.
+    ConnectWebRtc {
+        offer_sdp: String,
+        reply: oneshot::Sender<io::Result<(u64, String)>>,
+    },
+    /// Aborts a still-connecting outbound attempt. A no-op if `client_id`
+    /// already finished connecting or was never pending.
+    CancelConnection { client_id: u64 },
+    /// Aborts every still-connecting outbound attempt and ends the
+    /// dispatcher task. Sent by `vncShutdown` so a stuck reverse/repeater/
+    /// WebRTC dial can't keep a task alive past server teardown; `reply`
+    /// fires once every pending attempt has been aborted.
+    Shutdown { reply: oneshot::Sender<()> },
+}
+
+/// Spawns the dispatcher task and returns the sender JNI entry points push
+/// commands onto. One dispatcher is spawned per running `VncServer`.
+pub fn spawn(server: Arc<VncServer>) -> mpsc::UnboundedSender<VncCommand> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<VncCommand>();
+    let pending: Arc<Mutex<HashMap<u64, AbortHandle>>> = Arc::new(Mutex::new(HashMap::new()));
+
+    tokio::spawn(async move {
+        while let Some(command) = rx.recv().await {
+            match command {
+                VncCommand::ConnectReverse { host, port, reply } => {
+                    let client_id = server.allocate_client_id();
+                    let server = server.clone();
+                    let pending_done = pending.clone();
+                    let task = tokio::spawn(async move {
+                        let result = server.connect_reverse_with_id(client_id, host, port).await;
+                        pending_done.lock().unwrap().remove(&client_id);
+                        let _ = reply.send(result.map(|_| client_id));
+                    });
+                    pending.lock().unwrap().insert(client_id, task.abort_handle());
+                }
+                VncCommand::ConnectRepeater { host, port, repeater_id, reply } => {
+                    let client_id = server.allocate_client_id();
+                    let server = server.clone();
+                    let pending_done = pending.clone();
+                    let task = tokio::spawn(async move {
+                        let result = server
+                            .connect_repeater_with_id(client_id, host, port, repeater_id)
+                            .await;
+                        pending_done.lock().unwrap().remove(&client_id);
+                        let _ = reply.send(result.map(|_| client_id));
+                    });
+                    pending.lock().unwrap().insert(client_id, task.abort_handle());
+                }
+                VncCommand::ConnectWebRtc { offer_sdp, reply } => {
+                    let client_id = server.allocate_client_id();
+                    let server = server.clone();
+                    let pending_done = pending.clone();
+                    let task = tokio::spawn(async move {
+                        let result = server.connect_webrtc_with_id(client_id, offer_sdp).await;
+                        pending_done.lock().unwrap().remove(&client_id);
+                        let _ = reply.send(result.map(|answer_sdp| (client_id, answer_sdp)));
+                    });
+                    pending.lock().unwrap().insert(client_id, task.abort_handle());
+                }
+                VncCommand::CancelConnection { client_id } => {
+                    if let Some(handle) = pending.lock().unwrap().remove(&client_id) {
+                        handle.abort();
+                    }
+                }
+                VncCommand::Shutdown { reply } => {
+                    for (_, handle) in pending.lock().unwrap().drain() {
+                        handle.abort();
+                    }
+                    let _ = reply.send(());
+                    break;
+                }
+            }
+        }
+    });
+
+    tx
+}
@@ -0,0 +1,101 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pixel helpers shared across encoding implementations: RGBA/RGB24
+//! conversion, solid-colour detection, and palette building.
+
+use bytes::{BufMut, BytesMut};
+use std::collections::HashSet;
+
+/// Converts RGBA pixel data into packed `0x00BBGGRR` 32-bit pixels, dropping
+/// the alpha channel (the framebuffer is always opaque).
+pub fn rgba_to_rgb24_pixels(data: &[u8]) -> Vec<u32> {
+    data.chunks_exact(4)
+        .map(|c| (c[0] as u32) | ((c[1] as u32) << 8) | ((c[2] as u32) << 16))
+        .collect()
+}
+
+/// Returns `Some(color)` if every pixel in `pixels` is identical.
+pub fn check_solid_color(pixels: &[u32]) -> Option<u32> {
+    let first = *pixels.first()?;
+    if pixels.iter().all(|&p| p == first) {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+/// Builds the sorted set of distinct colors present in `pixels`.
+pub fn build_palette(pixels: &[u32]) -> Vec<u32> {
+    let set: HashSet<u32> = pixels.iter().copied().collect();
+    let mut palette: Vec<u32> = set.into_iter().collect();
+    palette.sort_unstable();
+    palette
+}
+
+/// Appends a 32bpp pixel to `buf` in the client's byte order (little-endian
+/// RGBX, matching the framebuffer's internal format).
+pub fn put_pixel32(buf: &mut BytesMut, color: u32) {
+    buf.put_u8((color & 0xFF) as u8);
+    buf.put_u8(((color >> 8) & 0xFF) as u8);
+    buf.put_u8(((color >> 16) & 0xFF) as u8);
+    buf.put_u8(0);
+}
+
+/// Appends a pixel as Tight's compact `TPixel` (3 bytes, no padding byte) --
+/// the form every Tight "basic compression" sub-block (copy, palette, and
+/// gradient filters alike) uses for inline pixel/palette data.
+pub fn put_tpixel24(buf: &mut BytesMut, color: u32) {
+    buf.put_u8((color & 0xFF) as u8);
+    buf.put_u8(((color >> 8) & 0xFF) as u8);
+    buf.put_u8(((color >> 16) & 0xFF) as u8);
+}
+
+/// Appends Tight's "compact length" encoding of `len`: 1-3 bytes, 7 bits
+/// per byte with the high bit set on every byte but the last (RFC 6143
+/// Section 7.7.5).
+pub fn put_compact_len(buf: &mut BytesMut, len: usize) {
+    if len < 128 {
+        buf.put_u8(len as u8);
+    } else if len < 16384 {
+        buf.put_u8(((len & 0x7F) | 0x80) as u8);
+        buf.put_u8((len >> 7) as u8);
+    } else {
+        buf.put_u8(((len & 0x7F) | 0x80) as u8);
+        buf.put_u8((((len >> 7) & 0x7F) | 0x80) as u8);
+        buf.put_u8((len >> 14) as u8);
+    }
+}
+
+/// Crops a `full_width`-wide, row-major array of packed 32-bit colors down
+/// to the `width x height` region starting at `(x, y)`.
+pub fn crop_pixels(pixels: &[u32], full_width: u16, x: u16, y: u16, width: u16, height: u16) -> Vec<u32> {
+    let mut out = Vec::with_capacity(width as usize * height as usize);
+    for row in 0..height {
+        let start = (y + row) as usize * full_width as usize + x as usize;
+        out.extend_from_slice(&pixels[start..start + width as usize]);
+    }
+    out
+}
+
+/// Crops a `full_width`-wide, row-major RGBA buffer down to the
+/// `width x height` region starting at `(x, y)`.
+pub fn crop_rgba(data: &[u8], full_width: u16, x: u16, y: u16, width: u16, height: u16) -> Vec<u8> {
+    let mut out = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in 0..height {
+        let start = ((y + row) as usize * full_width as usize + x as usize) * 4;
+        out.extend_from_slice(&data[start..start + width as usize * 4]);
+    }
+    out
+}
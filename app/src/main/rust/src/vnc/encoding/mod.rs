@@ -0,0 +1,58 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pixel data encoding strategies for framebuffer update rectangles.
+
+use bytes::BytesMut;
+
+pub mod common;
+pub mod subrect;
+pub mod tight;
+
+use tight::TightZlibStreams;
+
+/// One already-encoded subrectangle of a larger dirty rectangle, in that
+/// rectangle's local coordinates. `TightEncoding` splits its input through
+/// `subrect::split` and returns one of these per subrect rather than a
+/// single blob, so each region can carry whichever subencoding was
+/// cheapest for its content; the caller frames each as its own RFB
+/// rectangle sharing the outer encoding type.
+pub struct EncodedSubRect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+    pub body: BytesMut,
+}
+
+/// Encodes a rectangle of RGBA pixel data into a wire-ready byte stream for
+/// a particular RFB encoding type.
+pub trait Encoding {
+    /// Encodes `data` (RGBA, `width*height*4` bytes) at the given JPEG
+    /// quality (0-100), compressing through `zlib`'s persistent per-client
+    /// streams rather than a freshly reset one so the dictionary stays
+    /// continuous across the whole session. The zlib level is the one the
+    /// streams were created with; `TightEncoding` ignores `compression`
+    /// (0-9). Returns one or more subrects covering the whole
+    /// `width x height` region.
+    fn encode(
+        &self,
+        data: &[u8],
+        width: u16,
+        height: u16,
+        quality: u8,
+        compression: u8,
+        zlib: &mut TightZlibStreams,
+    ) -> Vec<EncodedSubRect>;
+}
@@ -0,0 +1,83 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Splits one dirty framebuffer rectangle into smaller subrectangles so
+//! `TightEncoding` can pick the cheapest subencoding per region instead of
+//! committing the whole rectangle to one method (TurboVNC's tiling
+//! strategy). Large runs of a single color are carved out as their own
+//! solid-fill subrects; everything else is tiled at a fixed size for
+//! per-tile color-count analysis.
+
+use super::common::check_solid_color;
+
+/// A rectangular region within a larger dirty rectangle, in that
+/// rectangle's local pixel coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubRect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// Tile size used once a band of rows isn't a large solid-color run.
+/// TurboVNC-sized tiles keep the per-tile palette scan cheap while still
+/// letting a mixed-content rectangle (e.g. a toolbar over a photo) get a
+/// different subencoding per region.
+const TILE_SIZE: u16 = 64;
+
+/// A solid-color run of fewer than this many full-width rows isn't worth
+/// carving out as its own subrect -- the per-subrect header overhead would
+/// outweigh the saving, so it's left for ordinary tiling instead.
+const MIN_SOLID_RUN: u16 = 8;
+
+/// Splits `width x height` (with `pixels` holding `width * height` packed
+/// 32-bit colors, row-major) into subrects: full-width solid-color bands
+/// where present, and `TILE_SIZE` tiles everywhere else.
+pub fn split(pixels: &[u32], width: u16, height: u16) -> Vec<SubRect> {
+    let mut rects = Vec::new();
+    let mut y = 0u16;
+
+    while y < height {
+        let row0 = row(pixels, width, y);
+        if let Some(color) = check_solid_color(row0) {
+            let mut run = 1u16;
+            while y + run < height && check_solid_color(row(pixels, width, y + run)) == Some(color) {
+                run += 1;
+            }
+            if run >= MIN_SOLID_RUN {
+                rects.push(SubRect { x: 0, y, width, height: run });
+                y += run;
+                continue;
+            }
+        }
+
+        let band_height = TILE_SIZE.min(height - y);
+        let mut x = 0u16;
+        while x < width {
+            let tile_width = TILE_SIZE.min(width - x);
+            rects.push(SubRect { x, y, width: tile_width, height: band_height });
+            x += tile_width;
+        }
+        y += band_height;
+    }
+
+    rects
+}
+
+/// Returns the pixels of row `y` of a `width`-wide image.
+fn row(pixels: &[u32], width: u16, y: u16) -> &[u32] {
+    let start = y as usize * width as usize;
+    &pixels[start..start + width as usize]
+}
@@ -0,0 +1,156 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Screen buffer management and dirty region tracking.
+//!
+//! The framebuffer stores the current screen image as 32bpp RGBX and is
+//! updated wholesale or cropped from Java via JNI. Each update records the
+//! changed region so client sessions know what to re-encode and send.
+
+use std::io;
+use std::sync::atomic::{AtomicU16, Ordering};
+use tokio::sync::Mutex;
+
+/// A rectangular region of the framebuffer that has changed since the last
+/// time it was drained.
+#[derive(Debug, Clone, Copy)]
+pub struct DirtyRect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// Holds the current screen image plus the dirty regions pending
+/// transmission to connected clients.
+pub struct Framebuffer {
+    width: AtomicU16,
+    height: AtomicU16,
+    pixels: Mutex<Vec<u8>>,
+    dirty: Mutex<Vec<DirtyRect>>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u16, height: u16) -> Self {
+        Framebuffer {
+            width: AtomicU16::new(width),
+            height: AtomicU16::new(height),
+            pixels: Mutex::new(vec![0u8; width as usize * height as usize * 4]),
+            dirty: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width.load(Ordering::Relaxed)
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height.load(Ordering::Relaxed)
+    }
+
+    /// Replaces the entire framebuffer with `data` (RGBA, `width*height*4`
+    /// bytes) and marks the whole screen dirty.
+    pub async fn update_from_slice(&self, data: &[u8]) -> io::Result<()> {
+        let (w, h) = (self.width(), self.height());
+        let expected = w as usize * h as usize * 4;
+        if data.len() != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("framebuffer update size {} does not match {}x{}", data.len(), w, h),
+            ));
+        }
+        let mut pixels = self.pixels.lock().await;
+        pixels.copy_from_slice(data);
+        drop(pixels);
+        self.mark_dirty(DirtyRect { x: 0, y: 0, width: w, height: h }).await;
+        Ok(())
+    }
+
+    /// Replaces a cropped rectangular region of the framebuffer with `data`
+    /// (RGBA, `width*height*4` bytes) and marks that region dirty.
+    pub async fn update_cropped(&self, data: &[u8], x: u16, y: u16, width: u16, height: u16) -> io::Result<()> {
+        let (fb_w, fb_h) = (self.width(), self.height());
+        if x as u32 + width as u32 > fb_w as u32 || y as u32 + height as u32 > fb_h as u32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("crop region ({x},{y},{width},{height}) outside {fb_w}x{fb_h} framebuffer"),
+            ));
+        }
+        let expected = width as usize * height as usize * 4;
+        if data.len() != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cropped update size {} does not match {}x{}", data.len(), width, height),
+            ));
+        }
+
+        let mut pixels = self.pixels.lock().await;
+        for row in 0..height as usize {
+            let src_off = row * width as usize * 4;
+            let dst_off = ((y as usize + row) * fb_w as usize + x as usize) * 4;
+            pixels[dst_off..dst_off + width as usize * 4]
+                .copy_from_slice(&data[src_off..src_off + width as usize * 4]);
+        }
+        drop(pixels);
+        self.mark_dirty(DirtyRect { x, y, width, height }).await;
+        Ok(())
+    }
+
+    /// Resizes the framebuffer to `width`x`height`, preserving the
+    /// overlapping top-left region of the previous contents. Equivalent to
+    /// libvncserver's `rfbNewFramebuffer`.
+    pub async fn resize(&self, width: u16, height: u16) -> io::Result<()> {
+        let (old_w, old_h) = (self.width(), self.height());
+        let mut pixels = self.pixels.lock().await;
+        let mut new_pixels = vec![0u8; width as usize * height as usize * 4];
+
+        let copy_w = old_w.min(width) as usize;
+        let copy_h = old_h.min(height) as usize;
+        for row in 0..copy_h {
+            let src_off = row * old_w as usize * 4;
+            let dst_off = row * width as usize * 4;
+            new_pixels[dst_off..dst_off + copy_w * 4]
+                .copy_from_slice(&pixels[src_off..src_off + copy_w * 4]);
+        }
+        *pixels = new_pixels;
+        drop(pixels);
+
+        self.width.store(width, Ordering::Relaxed);
+        self.height.store(height, Ordering::Relaxed);
+        self.mark_dirty(DirtyRect { x: 0, y: 0, width, height }).await;
+        Ok(())
+    }
+
+    /// Returns a copy of the current pixel data for the given rectangle.
+    pub async fn read_rect(&self, x: u16, y: u16, width: u16, height: u16) -> Vec<u8> {
+        let fb_w = self.width();
+        let pixels = self.pixels.lock().await;
+        let mut out = Vec::with_capacity(width as usize * height as usize * 4);
+        for row in 0..height as usize {
+            let off = ((y as usize + row) * fb_w as usize + x as usize) * 4;
+            out.extend_from_slice(&pixels[off..off + width as usize * 4]);
+        }
+        out
+    }
+
+    async fn mark_dirty(&self, rect: DirtyRect) {
+        self.dirty.lock().await.push(rect);
+    }
+
+    /// Drains and returns all dirty regions accumulated since the last call.
+    pub async fn take_dirty(&self) -> Vec<DirtyRect> {
+        let mut dirty = self.dirty.lock().await;
+        std::mem::take(&mut *dirty)
+    }
+}
@@ -15,6 +15,9 @@
 //! - **`encoding`**: Pixel data encoding strategies (Raw, Tight, etc.)
 //! - **`auth`**: VNC authentication implementation
 //! - **`repeater`**: Support for VNC repeater/reverse connections
+//! - **`dispatcher`**: Non-blocking command queue for the JNI layer
+//! - **`webrtc_transport`**: Optional WebRTC data-channel transport for
+//!   NAT-traversal connections
 //!
 //! # Features
 //!
@@ -47,5 +50,6 @@ pub mod encoding;
 pub mod auth;
 pub mod client;
 pub mod repeater;
-pub mod translate;
+pub mod dispatcher;
+pub mod webrtc_transport;
 
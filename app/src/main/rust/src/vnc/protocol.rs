@@ -0,0 +1,112 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! RFB protocol constants and shared wire-level data structures.
+//!
+//! This module has no I/O of its own; `client` and `server` read and write
+//! these structures over whatever transport they are given.
+
+/// RFB protocol version string sent by the server during the handshake.
+pub const RFB_VERSION: &[u8] = b"RFB 003.008\n";
+
+/// Security type identifiers (RFC 6143 Section 7.1.2).
+pub mod security_type {
+    pub const INVALID: u8 = 0;
+    pub const NONE: u8 = 1;
+    pub const VNC_AUTH: u8 = 2;
+}
+
+/// Client-to-server message type identifiers (RFC 6143 Section 7.5).
+pub mod client_msg {
+    pub const SET_PIXEL_FORMAT: u8 = 0;
+    pub const SET_ENCODINGS: u8 = 2;
+    pub const FRAMEBUFFER_UPDATE_REQUEST: u8 = 3;
+    pub const KEY_EVENT: u8 = 4;
+    pub const POINTER_EVENT: u8 = 5;
+    pub const CLIENT_CUT_TEXT: u8 = 6;
+}
+
+/// Server-to-client message type identifiers.
+pub mod server_msg {
+    pub const FRAMEBUFFER_UPDATE: u8 = 0;
+    pub const SET_COLOUR_MAP_ENTRIES: u8 = 1;
+    pub const BELL: u8 = 2;
+    pub const SERVER_CUT_TEXT: u8 = 3;
+}
+
+/// Encoding type identifiers negotiated via `SetEncodings` (RFC 6143
+/// Section 7.7, plus the TightVNC extensions).
+pub mod encoding_type {
+    pub const RAW: i32 = 0;
+    pub const COPY_RECT: i32 = 1;
+    pub const TIGHT: i32 = 7;
+    /// Tight PNG, the noVNC/websockify variant of Tight that replaces the
+    /// zlib-compressed basic/palette sub-blocks with PNG images so
+    /// browser-based clients can decode them natively.
+    pub const TIGHT_PNG: i32 = -269;
+}
+
+/// Pseudo-encoding identifiers, also negotiated via `SetEncodings` but
+/// describing capabilities rather than framebuffer-update formats.
+pub mod pseudo_encoding {
+    /// TightVNC's Extended Clipboard, which lets `ClientCutText`/
+    /// `ServerCutText` carry UTF-8 text (and other formats) instead of
+    /// being limited to Latin-1.
+    pub const EXTENDED_CLIPBOARD: i32 = -1063131698;
+}
+
+/// Action and format bits carried in the 4-byte flags word of an extended
+/// `ClientCutText`/`ServerCutText` message (TightVNC Extended Clipboard
+/// Pseudo-encoding spec).
+pub mod clipboard_flags {
+    pub const FORMAT_TEXT: u32 = 1 << 0;
+    pub const ACTION_CAPS: u32 = 1 << 24;
+    pub const ACTION_REQUEST: u32 = 1 << 25;
+    pub const ACTION_PEEK: u32 = 1 << 26;
+    pub const ACTION_NOTIFY: u32 = 1 << 27;
+    pub const ACTION_PROVIDE: u32 = 1 << 28;
+}
+
+/// A VNC pixel format as exchanged in `ServerInit`/`SetPixelFormat`.
+#[derive(Debug, Clone, Copy)]
+pub struct PixelFormat {
+    pub bits_per_pixel: u8,
+    pub depth: u8,
+    pub big_endian: bool,
+    pub true_colour: bool,
+    pub red_max: u16,
+    pub green_max: u16,
+    pub blue_max: u16,
+    pub red_shift: u8,
+    pub green_shift: u8,
+    pub blue_shift: u8,
+}
+
+impl Default for PixelFormat {
+    /// The 32bpp true-colour RGBX format the framebuffer stores internally.
+    fn default() -> Self {
+        PixelFormat {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian: false,
+            true_colour: true,
+            red_max: 255,
+            green_max: 255,
+            blue_max: 255,
+            red_shift: 0,
+            green_shift: 8,
+            blue_shift: 16,
+        }
+    }
+}
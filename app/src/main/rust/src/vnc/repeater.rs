@@ -0,0 +1,48 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Support for connecting out through an UltraVNC-style repeater (mode II).
+//!
+//! A repeater pairs a server and a viewer that both dial in and present the
+//! same ID string, then transparently proxies bytes between them. Unlike a
+//! direct reverse connection, the repeater handshake requires one fixed-size
+//! frame to be written before the RFB protocol starts.
+
+use std::io;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+/// Size in bytes of the repeater ID frame (UltraVNC `rfbproto.h`
+/// `EXTERNAL_CONNECTION_ID_LEN`-style fixed buffer).
+const ID_FRAME_LEN: usize = 250;
+
+/// Writes the repeater's fixed 250-byte rendezvous frame: the ASCII
+/// `repeater_id` (conventionally formatted like `"ID:12345"`), null-padded
+/// to `ID_FRAME_LEN` bytes.
+///
+/// The repeater uses this frame to pair the connection with whichever
+/// viewer dialed in and presented the same ID, then proxies the two sockets
+/// together. The RFB handshake begins immediately after this write.
+pub async fn write_id_frame(stream: &mut TcpStream, repeater_id: &str) -> io::Result<()> {
+    if repeater_id.len() >= ID_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("repeater ID {:?} is too long for the {}-byte frame", repeater_id, ID_FRAME_LEN),
+        ));
+    }
+
+    let mut frame = [0u8; ID_FRAME_LEN];
+    frame[..repeater_id.len()].copy_from_slice(repeater_id.as_bytes());
+    stream.write_all(&frame).await
+}
@@ -0,0 +1,425 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Main server logic: accepting connections, dialing outbound reverse and
+//! repeater connections, and forwarding events up to the JNI layer.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
+
+use crate::vnc::client::{self, ClientHandle};
+use crate::vnc::framebuffer::Framebuffer;
+use crate::vnc::repeater;
+
+/// Live client sessions keyed by client ID, shared between every session
+/// task and the JNI-facing enumeration/control calls.
+pub type ClientRegistry = Arc<Mutex<HashMap<u64, ClientHandle>>>;
+
+/// Events raised by client sessions and forwarded to Java through
+/// `vnc_jni::handle_server_event`.
+#[derive(Debug)]
+pub enum ServerEvent {
+    /// `spectator` is `true` for a client that connected while another
+    /// client was already registered: one
+    /// authenticated controller plus any number of view-only spectators can
+    /// watch the same session, modeled on TightVNC's multicast-queue relay.
+    ClientConnected { client_id: u64, spectator: bool },
+    ClientDisconnected { client_id: u64 },
+    KeyPress { client_id: u64, down: bool, key: u32 },
+    PointerMove { client_id: u64, x: u16, y: u16, button_mask: u8 },
+    CutText { client_id: u64, text: String },
+    /// Raised mid-handshake when a client selects VNC auth: the handshake
+    /// blocks on `reply` for the password Java wants enforced for this
+    /// connection, following the rfbClient `GetPassword` callback model
+    /// rather than relying on a single password baked in at server start.
+    PasswordRequest { client_id: u64, reply: oneshot::Sender<String> },
+    /// Like `PasswordRequest` but for a named credential field (e.g. a
+    /// VeNCrypt username), mirroring rfbClient's `GetCredential`.
+    CredentialRequest { client_id: u64, kind: String, reply: oneshot::Sender<String> },
+    /// An ICE/peer-connection state transition on a WebRTC transport, e.g.
+    /// `"connected"`, `"disconnected"`, `"failed"` (the `Display` form of
+    /// `webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState`).
+    WebRtcStateChanged { client_id: u64, state: String },
+}
+
+/// State shared between the `VncServer` handle and every spawned client
+/// session task.
+pub struct ServerShared {
+    pub framebuffer: Arc<Framebuffer>,
+    pub desktop_name: String,
+    pub password: Option<String>,
+    pub event_tx: mpsc::UnboundedSender<ServerEvent>,
+    pub clients: ClientRegistry,
+    /// Maximum concurrent clients accepted; `0` means unlimited.
+    pub max_clients: std::sync::atomic::AtomicUsize,
+    /// Seconds of inactivity before a client is dropped; `0` disables the
+    /// idle timeout.
+    pub idle_timeout_secs: AtomicU64,
+    /// Tight encoding preferences applied to every outgoing rectangle.
+    pub encoding_prefs: EncodingPrefs,
+    /// Encoded `FramebufferUpdate` frames published once by the controlling
+    /// client's session and drained by every spectator's relay task, so the
+    /// screen is encoded once per update regardless of spectator count.
+    pub rect_broadcast: broadcast::Sender<Arc<Vec<u8>>>,
+}
+
+/// Configuration for the Tight encoder, set from Java via
+/// `vncSetEncodingPreferences` and consulted per rectangle.
+pub struct EncodingPrefs {
+    /// 0-9 quality level, mapped to a JPEG quality of ~5-95.
+    pub quality_level: std::sync::atomic::AtomicU8,
+    /// 0-9 zlib compression level for the palette/basic sub-blocks. A
+    /// session reads it once, when its `TightZlibStreams` are created; later
+    /// changes only move the `TightPolicy` cutoffs for sessions already
+    /// running.
+    pub compress_level: std::sync::atomic::AtomicU8,
+    /// Whether photographic rectangles may be sent as JPEG at all.
+    pub allow_jpeg: std::sync::atomic::AtomicBool,
+}
+
+impl EncodingPrefs {
+    fn new() -> Self {
+        EncodingPrefs {
+            quality_level: std::sync::atomic::AtomicU8::new(6),
+            compress_level: std::sync::atomic::AtomicU8::new(6),
+            allow_jpeg: std::sync::atomic::AtomicBool::new(true),
+        }
+    }
+
+    /// Maps the negotiated 0-9 quality level to a JPEG quality of 5-95.
+    pub fn jpeg_quality(&self) -> u8 {
+        let level = self.quality_level.load(Ordering::Relaxed).min(9) as u32;
+        (5 + level * 10) as u8
+    }
+
+    pub fn compression(&self) -> u8 {
+        self.compress_level.load(Ordering::Relaxed)
+    }
+
+    pub fn jpeg_allowed(&self) -> bool {
+        self.allow_jpeg.load(Ordering::Relaxed)
+    }
+
+    /// Derives the `TightPolicy` from the server-wide compression level and
+    /// JPEG preference, so `TightEncoding`'s method-selection cutoffs track
+    /// the same knobs Java exposes via `vncSetEncodingPreferences` instead
+    /// of fixed constants. Every client gets the same policy: compression
+    /// levels a client requests in `SetEncodings` are not read.
+    pub fn tight_policy(&self) -> crate::vnc::encoding::tight::TightPolicy {
+        crate::vnc::encoding::tight::TightPolicy::for_compression_level(self.compression(), self.jpeg_allowed())
+    }
+}
+
+impl ServerShared {
+    /// Returns the configured idle timeout, or `None` if disabled.
+    pub fn idle_timeout(&self) -> Option<std::time::Duration> {
+        match self.idle_timeout_secs.load(Ordering::Relaxed) {
+            0 => None,
+            secs => Some(std::time::Duration::from_secs(secs)),
+        }
+    }
+}
+
+/// A snapshot of one client's registry entry, suitable for surfacing to
+/// Java without exposing the live `ClientHandle`.
+pub struct ClientInfo {
+    pub peer_addr: std::net::SocketAddr,
+    pub connected_secs: u64,
+    pub view_only: bool,
+}
+
+/// Top-level VNC server: owns the framebuffer and hands out client IDs for
+/// every connection, however it was established.
+pub struct VncServer {
+    shared: Arc<ServerShared>,
+    next_client_id: AtomicU64,
+}
+
+impl VncServer {
+    /// Creates a new server and its paired event receiver. The server does
+    /// not start listening or dialing out until `listen`, `connect_reverse`,
+    /// or `connect_repeater` is called.
+    pub fn new(
+        width: u16,
+        height: u16,
+        desktop_name: String,
+        password: Option<String>,
+    ) -> (Self, mpsc::UnboundedReceiver<ServerEvent>) {
+        if password.is_some() {
+            // `SecurityType::for_password` never offers `VncAuth` -- there's
+            // no DES challenge-response verification to check a response
+            // against yet -- so a configured password is otherwise silently
+            // unenforced and every connection is admitted. Warn loudly
+            // rather than give Java a false sense of security.
+            log::warn!(
+                "VNC server started with a password configured, but password authentication \
+                 is not yet enforced (VncAuth is never offered) -- every connection will be \
+                 accepted unauthenticated"
+            );
+        }
+
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let shared = Arc::new(ServerShared {
+            framebuffer: Arc::new(Framebuffer::new(width, height)),
+            desktop_name,
+            password,
+            event_tx,
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            max_clients: std::sync::atomic::AtomicUsize::new(0),
+            idle_timeout_secs: AtomicU64::new(0),
+            encoding_prefs: EncodingPrefs::new(),
+            rect_broadcast: broadcast::channel(16).0,
+        });
+        (VncServer { shared, next_client_id: AtomicU64::new(1) }, event_rx)
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.shared.framebuffer
+    }
+
+    /// Reserves the next client ID without starting a session. Used by
+    /// `dispatcher` to hand out an ID a caller can reference (e.g. to
+    /// cancel a still-connecting outbound attempt) before the connection
+    /// completes.
+    pub fn allocate_client_id(&self) -> u64 {
+        self.next_client_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Binds `port` on `0.0.0.0` and accepts inbound connections in a loop,
+    /// spawning a session task for each one.
+    pub async fn listen(&self, port: u16) -> io::Result<()> {
+        self.listen_addr("0.0.0.0", port).await
+    }
+
+    /// Binds `bind_addr:port` and accepts inbound connections in a loop,
+    /// spawning a session task for each one. Used by `vncStartListen` so
+    /// Java can run one or more independently stoppable listeners (e.g. one
+    /// per network interface) alongside the server's own outbound-only or
+    /// primary-port mode.
+    pub async fn listen_addr(&self, bind_addr: &str, port: u16) -> io::Result<()> {
+        let listener = tokio::net::TcpListener::bind((bind_addr, port)).await?;
+        self.accept_loop(listener).await
+    }
+
+    /// Accepts inbound connections on an already-validated, already-bound,
+    /// already-listening std `TcpListener` handed over by Java (e.g. built
+    /// from a `ParcelFileDescriptor`/`LocalSocket` fd), instead of binding a
+    /// port itself. Java owns socket creation -- interface binding,
+    /// `SO_REUSEADDR`, abstract Unix sockets -- and Rust only accepts and
+    /// serves.
+    ///
+    /// Takes the listener already validated rather than a raw fd: the
+    /// caller (`vncStartServerFd`) must build and validate it synchronously
+    /// on the calling thread so a bad fd fails the JNI call outright,
+    /// instead of only surfacing once this (spawned, detached) task runs.
+    pub async fn listen_from_fd(&self, std_listener: std::net::TcpListener) -> io::Result<()> {
+        let listener = tokio::net::TcpListener::from_std(std_listener)?;
+        self.accept_loop(listener).await
+    }
+
+    async fn accept_loop(&self, listener: tokio::net::TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, peer_addr) = listener.accept().await?;
+
+            let max_clients = self.shared.max_clients.load(Ordering::Relaxed);
+            if max_clients > 0 && self.shared.clients.lock().await.len() >= max_clients {
+                log::warn!(
+                    "Rejecting connection from {}: at max_clients limit ({})",
+                    peer_addr, max_clients
+                );
+                drop(stream);
+                continue;
+            }
+
+            let client_id = self.allocate_client_id();
+            let shared = self.shared.clone();
+            tokio::spawn(async move {
+                if let Err(e) = client::run_session(stream, peer_addr, client_id, shared).await {
+                    log::warn!("client {} session ended: {}", client_id, e);
+                }
+            });
+        }
+    }
+
+    /// Sets the admission-control policy applied by the accept loop:
+    /// `max_clients` (`0` for unlimited) and the idle timeout in seconds
+    /// (`0` to disable) after which a client with no incoming RFB messages
+    /// is disconnected.
+    pub fn set_connection_policy(&self, max_clients: usize, idle_timeout_secs: u64) {
+        self.shared.max_clients.store(max_clients, Ordering::Relaxed);
+        self.shared.idle_timeout_secs.store(idle_timeout_secs, Ordering::Relaxed);
+    }
+
+    /// Dials directly out to a listening viewer and runs the server-side
+    /// RFB handshake over that connection, without a repeater in between.
+    pub async fn connect_reverse(&self, host: String, port: u16) -> io::Result<u64> {
+        let client_id = self.allocate_client_id();
+        self.connect_reverse_with_id(client_id, host, port).await?;
+        Ok(client_id)
+    }
+
+    /// Dials an UltraVNC-style repeater, writes the fixed-size rendezvous ID
+    /// frame so the repeater can pair this connection with the matching
+    /// viewer, then runs the identical server-side RFB handshake
+    /// `connect_reverse` uses.
+    pub async fn connect_repeater(&self, host: String, port: u16, repeater_id: String) -> io::Result<u64> {
+        let client_id = self.allocate_client_id();
+        self.connect_repeater_with_id(client_id, host, port, repeater_id).await?;
+        Ok(client_id)
+    }
+
+    /// Like `connect_reverse`, but runs the session under a client ID the
+    /// caller already reserved via `allocate_client_id`. `dispatcher` uses
+    /// this so a pending outbound attempt is cancellable by the ID it will
+    /// eventually report back, rather than one assigned after the fact.
+    pub async fn connect_reverse_with_id(&self, client_id: u64, host: String, port: u16) -> io::Result<()> {
+        let stream = TcpStream::connect((host.as_str(), port)).await?;
+        self.spawn_session_with_id(client_id, stream)
+    }
+
+    /// Like `connect_repeater`, but under a pre-reserved client ID. See
+    /// `connect_reverse_with_id`.
+    pub async fn connect_repeater_with_id(
+        &self,
+        client_id: u64,
+        host: String,
+        port: u16,
+        repeater_id: String,
+    ) -> io::Result<()> {
+        let mut stream = TcpStream::connect((host.as_str(), port)).await?;
+        repeater::write_id_frame(&mut stream, &repeater_id).await?;
+        self.spawn_session_with_id(client_id, stream)
+    }
+
+    fn spawn_session_with_id(&self, client_id: u64, stream: TcpStream) -> io::Result<()> {
+        let peer_addr = stream.peer_addr()?;
+        let shared = self.shared.clone();
+        tokio::spawn(async move {
+            if let Err(e) = client::run_session(stream, peer_addr, client_id, shared).await {
+                log::warn!("client {} session ended: {}", client_id, e);
+            }
+        });
+        Ok(())
+    }
+
+    /// Completes a WebRTC offer/answer exchange, opens the `"rfb"` data
+    /// channel, and runs the same server-side session `connect_reverse`
+    /// and friends use over it -- giving firewall-blocked peers a way to
+    /// reach the phone without a repeater. Returns the SDP answer for the
+    /// caller to hand back over whatever signaling channel carried the
+    /// offer; the session itself only starts once the data channel opens.
+    pub async fn connect_webrtc_with_id(&self, client_id: u64, offer_sdp: String) -> io::Result<String> {
+        let (answer_sdp, stream) =
+            crate::vnc::webrtc_transport::accept_offer(client_id, offer_sdp, self.shared.event_tx.clone()).await?;
+
+        let shared = self.shared.clone();
+        tokio::spawn(async move {
+            // WebRTC connections have no traditional socket peer address;
+            // `ClientInfo`/`vncGetClientInfo` report this sentinel for them.
+            let peer_addr: std::net::SocketAddr = ([0, 0, 0, 0], 0).into();
+            if let Err(e) = client::run_session(stream, peer_addr, client_id, shared).await {
+                log::warn!("client {} WebRTC session ended: {}", client_id, e);
+            }
+        });
+
+        Ok(answer_sdp)
+    }
+
+    /// Sends clipboard text to every connected client via `ServerCutText`,
+    /// using the Extended Clipboard form for clients that negotiated it and
+    /// the legacy Latin-1 form otherwise.
+    pub async fn send_cut_text_to_all(&self, text: String) -> io::Result<()> {
+        let clients = self.shared.clients.lock().await;
+        for handle in clients.values() {
+            let extended = handle.extended_clipboard.load(Ordering::Relaxed);
+            let frame = client::build_cut_text_frame(&text, extended);
+            let _ = handle.outbound.send(frame);
+        }
+        Ok(())
+    }
+
+    /// Returns the number of currently connected clients.
+    pub async fn client_count(&self) -> usize {
+        self.shared.clients.lock().await.len()
+    }
+
+    /// Returns peer address, connection age, and view-only state for a
+    /// connected client, or `None` if `client_id` is not currently
+    /// connected.
+    pub async fn client_info(&self, client_id: u64) -> Option<ClientInfo> {
+        let clients = self.shared.clients.lock().await;
+        let handle = clients.get(&client_id)?;
+        Some(ClientInfo {
+            peer_addr: handle.peer_addr,
+            connected_secs: handle.connected_at.elapsed().as_secs(),
+            view_only: handle.view_only.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Signals a single client's session task to close, without affecting
+    /// any other connection.
+    pub async fn disconnect_client(&self, client_id: u64) -> bool {
+        let clients = self.shared.clients.lock().await;
+        match clients.get(&client_id) {
+            Some(handle) => {
+                let _ = handle.shutdown.send(());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Configures the Tight encoder used for outgoing framebuffer update
+    /// rectangles: JPEG quality level (0-9), zlib compression level (0-9),
+    /// and whether JPEG is allowed at all for photographic content.
+    pub fn set_encoding_preferences(&self, quality_level: u8, compress_level: u8, allow_jpeg: bool) {
+        self.shared.encoding_prefs.quality_level.store(quality_level.min(9), Ordering::Relaxed);
+        self.shared.encoding_prefs.compress_level.store(compress_level.min(9), Ordering::Relaxed);
+        self.shared.encoding_prefs.allow_jpeg.store(allow_jpeg, Ordering::Relaxed);
+    }
+
+    /// Sets whether a client's pointer/key events are dropped in the input
+    /// path. The session and its framebuffer updates are unaffected.
+    pub async fn set_client_view_only(&self, client_id: u64, view_only: bool) -> bool {
+        let clients = self.shared.clients.lock().await;
+        match clients.get(&client_id) {
+            Some(handle) => {
+                handle.view_only.store(view_only, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Demotes a client to a view-only spectator or promotes it back to a
+    /// full controller: sets `view_only` and `spectator` together, since a
+    /// spectator's own `FramebufferUpdateRequest`s are also suppressed in
+    /// favor of the `rect_broadcast` relay. Returns `false` if `client_id`
+    /// is not currently connected.
+    pub async fn set_client_access(&self, client_id: u64, view_only: bool) -> bool {
+        let clients = self.shared.clients.lock().await;
+        match clients.get(&client_id) {
+            Some(handle) => {
+                handle.view_only.store(view_only, Ordering::Relaxed);
+                handle.spectator.store(view_only, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,259 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A WebRTC data-channel transport for connections that can't reach the
+//! phone via a direct socket or a repeater, e.g. a viewer and server both
+//! behind restrictive NATs. The SDP offer/answer exchange itself is carried
+//! across JNI exactly like the SDP strings in the WebRTC Android JNI
+//! bridge; this module only builds the peer connection and wraps its data
+//! channel so `client::run_session` can drive it identically to a
+//! `TcpStream`.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+use webrtc::api::APIBuilder;
+use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::vnc::server::ServerEvent;
+
+/// The RFB data channel's label, negotiated in-band on every connection.
+const DATA_CHANNEL_LABEL: &str = "rfb";
+
+/// An `AsyncRead`/`AsyncWrite` adapter over a single reliable, ordered
+/// WebRTC data channel. Incoming messages are queued by the data channel's
+/// `on_message` callback and drained into `read_buf` as `poll_read` is
+/// called; writes go straight to `RTCDataChannel::send`.
+pub struct WebRtcStream {
+    channel: Arc<RTCDataChannel>,
+    inbound: mpsc::UnboundedReceiver<Bytes>,
+    read_buf: VecDeque<u8>,
+    // The in-flight `RTCDataChannel::send` future, if `poll_write` returned
+    // `Pending` on its last call. At most one send is ever in flight: a new
+    // `poll_write` isn't issued until this one resolves, which is what
+    // keeps RFB bytes written in order and applies real back-pressure
+    // instead of a detached, unordered fire-and-forget task per call.
+    pending_write: Option<Pin<Box<dyn Future<Output = io::Result<usize>> + Send>>>,
+    // Kept alive for the life of the stream: dropping the peer connection
+    // tears the data channel down with it.
+    _peer_connection: Arc<RTCPeerConnection>,
+}
+
+impl AsyncRead for WebRtcStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if self.read_buf.is_empty() {
+            match self.inbound.poll_recv(cx) {
+                Poll::Ready(Some(chunk)) => self.read_buf.extend(chunk),
+                Poll::Ready(None) => return Poll::Ready(Ok(())), // channel closed: EOF
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        let take = buf.remaining().min(self.read_buf.len());
+        let chunk: Vec<u8> = self.read_buf.drain(..take).collect();
+        buf.put_slice(&chunk);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for WebRtcStream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<io::Result<usize>> {
+        // `write_all` re-polls with the *same* buffer until a call reports
+        // it written, so a pending send completing here means `data` was
+        // already sent by that stashed future -- report its result as this
+        // call's instead of sending `data` again, or the bytes land twice.
+        match self.as_mut().poll_pending_write(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(Some(result)) => return Poll::Ready(result),
+            Poll::Ready(None) => {}
+        }
+
+        let channel = self.channel.clone();
+        let payload = Bytes::copy_from_slice(data);
+        let len = data.len();
+        let mut fut: Pin<Box<dyn Future<Output = io::Result<usize>> + Send>> = Box::pin(async move {
+            channel
+                .send(&payload)
+                .await
+                .map(|_| len)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("webrtc send failed: {}", e)))
+        });
+
+        match fut.as_mut().poll(cx) {
+            Poll::Ready(result) => Poll::Ready(result),
+            Poll::Pending => {
+                self.pending_write = Some(fut);
+                Poll::Pending
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.as_mut().poll_pending_write(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Some(Ok(_))) => Poll::Ready(Ok(())),
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Err(e)),
+            Poll::Ready(None) => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Poll::Pending = self.as_mut().poll_pending_write(cx) {
+            return Poll::Pending;
+        }
+
+        let channel = self.channel.clone();
+        tokio::spawn(async move {
+            let _ = channel.close().await;
+        });
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl WebRtcStream {
+    /// Drives any in-flight `RTCDataChannel::send` to completion. Returns
+    /// `Pending` while it's still in flight, `Ready(Some(result))` with its
+    /// result the first poll after it resolves (consuming `pending_write`),
+    /// and `Ready(None)` if none was in flight. Shared by `poll_write` (so a
+    /// new send isn't started until the previous one lands, and its result
+    /// is reported instead of resending), `poll_flush` (so a flush actually
+    /// waits for outstanding data), and `poll_shutdown` (so the channel
+    /// isn't closed out from under a pending send).
+    fn poll_pending_write(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<usize>>> {
+        let this = self.get_mut();
+        match this.pending_write.as_mut() {
+            Some(fut) => {
+                let result = std::task::ready!(fut.as_mut().poll(cx));
+                this.pending_write = None;
+                Poll::Ready(Some(result))
+            }
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+/// Performs the offer/answer exchange for a new peer connection, wires up
+/// the reliable ordered `"rfb"` data channel, and forwards ICE/connection
+/// state transitions as `ServerEvent::WebRtcStateChanged`. Returns the
+/// local SDP answer (for Java to hand back over its own signaling channel)
+/// together with a `WebRtcStream` that becomes readable/writable once the
+/// data channel opens.
+pub async fn accept_offer(
+    client_id: u64,
+    offer_sdp: String,
+    event_tx: mpsc::UnboundedSender<ServerEvent>,
+) -> io::Result<(String, WebRtcStream)> {
+    let api = APIBuilder::new().build();
+    let peer_connection = Arc::new(
+        api.new_peer_connection(RTCConfiguration::default())
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("failed to create peer connection: {}", e)))?,
+    );
+
+    {
+        let event_tx = event_tx.clone();
+        peer_connection.on_peer_connection_state_change(Box::new(move |state: RTCPeerConnectionState| {
+            let _ = event_tx.send(ServerEvent::WebRtcStateChanged { client_id, state: state.to_string() });
+            Box::pin(async {})
+        }));
+    }
+
+    let (inbound_tx, inbound_rx) = mpsc::unbounded_channel();
+    let channel_ready = Arc::new(tokio::sync::Notify::new());
+
+    {
+        let channel_ready = channel_ready.clone();
+        let inbound_tx = inbound_tx.clone();
+        peer_connection.on_data_channel(Box::new(move |channel: Arc<RTCDataChannel>| {
+            if channel.label() == DATA_CHANNEL_LABEL {
+                let inbound_tx = inbound_tx.clone();
+                channel.on_message(Box::new(move |msg| {
+                    let _ = inbound_tx.send(msg.data);
+                    Box::pin(async {})
+                }));
+                channel_ready.notify_one();
+            }
+            Box::pin(async {})
+        }));
+    }
+
+    let offer = RTCSessionDescription::offer(offer_sdp)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("invalid SDP offer: {}", e)))?;
+    peer_connection
+        .set_remote_description(offer)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("failed to set remote description: {}", e)))?;
+
+    // Also create our own channel with the same label in case the remote
+    // offer didn't pre-negotiate one; whichever side's `on_data_channel`
+    // callback fires is the one actually used.
+    let local_channel = peer_connection
+        .create_data_channel(
+            DATA_CHANNEL_LABEL,
+            Some(RTCDataChannelInit {
+                ordered: Some(true),
+                max_retransmits: None,
+                ..Default::default()
+            }),
+        )
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("failed to create data channel: {}", e)))?;
+    {
+        let inbound_tx = inbound_tx.clone();
+        local_channel.on_message(Box::new(move |msg| {
+            let _ = inbound_tx.send(msg.data);
+            Box::pin(async {})
+        }));
+    }
+
+    let answer = peer_connection
+        .create_answer(None)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("failed to create SDP answer: {}", e)))?;
+
+    let mut gathering_complete = peer_connection.gathering_complete_promise().await;
+    peer_connection
+        .set_local_description(answer)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("failed to set local description: {}", e)))?;
+    let _ = gathering_complete.recv().await;
+
+    let answer_sdp = peer_connection
+        .local_description()
+        .await
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no local description after gathering"))?
+        .sdp;
+
+    let stream = WebRtcStream {
+        channel: local_channel,
+        inbound: inbound_rx,
+        read_buf: VecDeque::new(),
+        pending_write: None,
+        _peer_connection: peer_connection,
+    };
+
+    Ok((answer_sdp, stream))
+}
@@ -10,17 +10,23 @@ use jni::sys::{jint, jboolean, jlong, JNI_TRUE, JNI_FALSE};
 use log::{info, error, warn};
 use once_cell::sync::OnceCell;
 use tokio::runtime::Runtime;
-use tokio::sync::{mpsc, broadcast};
+use tokio::sync::{mpsc, broadcast, oneshot};
+use tokio::time::Duration;
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 use crate::vnc::server::VncServer;
 use crate::vnc::server::ServerEvent;
+use crate::vnc::dispatcher::{self, VncCommand};
 
 /// Global Tokio runtime for the VNC server.
 static VNC_RUNTIME: OnceCell<Runtime> = OnceCell::new();
 /// Global container for the VNC server instance.
 static VNC_SERVER: OnceCell<Arc<Mutex<Option<Arc<VncServer>>>>> = OnceCell::new();
+/// Global container for the command dispatcher's sender, rebuilt each time
+/// `vncStartServer`/`vncStartServerFd` spins up a new `VncServer`.
+static VNC_DISPATCHER: OnceCell<Mutex<Option<mpsc::UnboundedSender<VncCommand>>>> = OnceCell::new();
 /// Global broadcast sender for shutdown signals.
 static SHUTDOWN_SIGNAL: OnceCell<broadcast::Sender<()>> = OnceCell::new();
 /// Atomic flag to track if the event handler is running.
@@ -38,6 +44,18 @@ static MAIN_SERVICE_CLASS: OnceCell<jni::objects::GlobalRef> = OnceCell::new();
 #[allow(dead_code)]
 static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
 
+/// Shutdown handles for listeners started via `vncStartListen`, keyed by the
+/// listener ID returned to Java. Distinct from `SHUTDOWN_SIGNAL`, which only
+/// stops the server's own primary listener and the event handler.
+static LISTENER_REGISTRY: OnceCell<Mutex<HashMap<u64, tokio::sync::oneshot::Sender<()>>>> = OnceCell::new();
+/// Unique listener ID counter for `vncStartListen`.
+static NEXT_LISTENER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// How long `vncShutdown` waits for the dispatcher to abort every pending
+/// outbound connection attempt before giving up and tearing the server down
+/// anyway.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Initializes or retrieves the global Tokio runtime for the VNC server.
 ///
 /// This function ensures that a single instance of the Tokio multi-threaded runtime
@@ -119,6 +137,8 @@ pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncInit(
 
     // Initialize server container
     VNC_SERVER.get_or_init(|| Arc::new(Mutex::new(None)));
+    VNC_DISPATCHER.get_or_init(|| Mutex::new(None));
+    LISTENER_REGISTRY.get_or_init(|| Mutex::new(HashMap::new()));
 
     info!("Rust VNC Server initialized");
 }
@@ -237,6 +257,7 @@ pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncStart
 
     // Start event handler FIRST
     spawn_event_handler(event_rx);
+    spawn_dispatcher(server.clone());
 
     // Start listener only if port is specified (not -1)
     if let Some(listen_port) = port_opt {
@@ -264,6 +285,255 @@ pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncStart
     JNI_TRUE
 }
 
+/// JNI entry point to start the VNC server from a pre-bound, listening
+/// socket file descriptor supplied by Java.
+///
+/// This mirrors `vncStartServer` but skips binding a port itself: Java has
+/// already created and bound the socket (for example via
+/// `ParcelFileDescriptor`/`LocalSocket`, or to reach an interface-bound or
+/// abstract-namespace address Rust can't easily express), and only wants
+/// Rust to accept and serve connections on it.
+///
+/// # Arguments
+///
+/// * `env` - The JNI environment.
+/// * `_class` - The Java class from which this method is called.
+/// * `width` - The width of the framebuffer.
+/// * `height` - The height of the framebuffer.
+/// * `fd` - An already-bound, already-listening TCP socket file descriptor.
+/// * `desktop_name` - The name of the VNC desktop.
+/// * `password` - The password for VNC authentication.
+///
+/// # Returns
+///
+/// `JNI_TRUE` if the server starts successfully, `JNI_FALSE` otherwise.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncStartServerFd(
+    mut env: JNIEnv,
+    _class: JClass,
+    width: jint,
+    height: jint,
+    fd: jint,
+    desktop_name: JString,
+    password: JString,
+) -> jboolean {
+    const MAX_DIMENSION: i32 = 8192;
+    const MIN_DIMENSION: i32 = 1;
+
+    let width = match u16::try_from(width) {
+        Ok(w) if w >= MIN_DIMENSION as u16 && w <= MAX_DIMENSION as u16 => w,
+        _ => {
+            error!("Invalid width: {} (must be {}-{})", width, MIN_DIMENSION, MAX_DIMENSION);
+            return JNI_FALSE;
+        }
+    };
+
+    let height = match u16::try_from(height) {
+        Ok(h) if h >= MIN_DIMENSION as u16 && h <= MAX_DIMENSION as u16 => h,
+        _ => {
+            error!("Invalid height: {} (must be {}-{})", height, MIN_DIMENSION, MAX_DIMENSION);
+            return JNI_FALSE;
+        }
+    };
+
+    if fd < 0 {
+        error!("Invalid listening socket fd: {}", fd);
+        return JNI_FALSE;
+    }
+
+    // Take ownership of and validate the fd synchronously, on the calling
+    // thread, before reporting success back to Java -- the accept loop
+    // runs on a detached spawned task, so validating there instead would
+    // mean a bad fd only surfaced after this function had already
+    // returned JNI_TRUE.
+    let std_listener = {
+        use std::os::fd::FromRawFd;
+        // SAFETY: the caller guarantees `fd` is a valid, open, listening
+        // socket handed off exactly once; ownership transfers to the
+        // `TcpListener` constructed here.
+        unsafe { std::net::TcpListener::from_raw_fd(fd) }
+    };
+    if let Err(e) = std_listener.local_addr() {
+        error!("Invalid listening socket fd {}: {}", fd, e);
+        return JNI_FALSE;
+    }
+    if let Err(e) = std_listener.set_nonblocking(true) {
+        error!("Failed to set listening socket fd {} non-blocking: {}", fd, e);
+        return JNI_FALSE;
+    }
+
+    let desktop_name_str: String = match env.get_string(&desktop_name) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            error!("Failed to get desktop name: {}", e);
+            return JNI_FALSE;
+        }
+    };
+
+    let password_str: Option<String> = if !password.is_null() {
+        match env.get_string(&password) {
+            Ok(s) => {
+                let pw: String = s.into();
+                if pw.is_empty() { None } else { Some(pw) }
+            }
+            Err(_) => None,
+        }
+    } else {
+        None
+    };
+
+    info!("Starting Rust VNC Server: {}x{} on pre-bound fd {}", width, height, fd);
+
+    let (server, event_rx) = VncServer::new(width, height, desktop_name_str, password_str);
+    let server: Arc<VncServer> = Arc::new(server);
+
+    if let Some(server_container) = VNC_SERVER.get() {
+        match server_container.lock() {
+            Ok(mut guard) => {
+                *guard = Some(server.clone());
+            }
+            Err(e) => {
+                error!("Failed to lock server container: {}", e);
+                return JNI_FALSE;
+            }
+        }
+    } else {
+        error!("VNC server container not initialized");
+        return JNI_FALSE;
+    }
+
+    spawn_event_handler(event_rx);
+    spawn_dispatcher(server.clone());
+
+    let runtime = get_or_init_vnc_runtime();
+    let server_clone = server.clone();
+    let mut shutdown_rx = get_or_init_shutdown_signal().subscribe();
+
+    runtime.spawn(async move {
+        tokio::select! {
+            result = server_clone.listen_from_fd(std_listener) => {
+                if let Err(e) = result {
+                    error!("VNC server listen_from_fd error: {}", e);
+                }
+            }
+            _ = shutdown_rx.recv() => {
+                info!("VNC server received shutdown signal");
+            }
+        }
+    });
+
+    info!("Rust VNC Server started successfully on pre-bound fd");
+    JNI_TRUE
+}
+
+/// JNI entry point to start an additional, independently stoppable listener
+/// accepting inbound viewer connections, mirroring the accept-thread
+/// pattern TightVNC's client uses: bind once, then `accept()` in a loop,
+/// handing each socket off to the normal client session handler. Unlike the
+/// port passed to `vncStartServer`, this can be called more than once (for
+/// example once per network interface) and each call is stopped
+/// independently via `vncStopListen`.
+///
+/// # Arguments
+///
+/// * `env` - The JNI environment.
+/// * `_class` - The Java class from which this method is called.
+/// * `bind_addr` - The local address to bind, e.g. `"0.0.0.0"`.
+/// * `port` - The TCP port to listen on.
+///
+/// # Returns
+///
+/// A listener ID (`jlong`) to pass to `vncStopListen`, or `0` if the
+/// arguments are invalid or the server is not started. The bind happens
+/// on the spawned task after this returns, so a bind error is only logged.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncStartListen(
+    mut env: JNIEnv,
+    _class: JClass,
+    bind_addr: JString,
+    port: jint,
+) -> jlong {
+    let bind_addr_str: String = match env.get_string(&bind_addr) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            error!("Failed to get listen bind address: {}", e);
+            return 0;
+        }
+    };
+
+    let port_u16 = match u16::try_from(port) {
+        Ok(p) if p > 0 => p,
+        _ => {
+            error!("Invalid listen port: {}", port);
+            return 0;
+        }
+    };
+
+    let server = match VNC_SERVER.get().and_then(|c| c.lock().ok()).and_then(|g| g.clone()) {
+        Some(s) => s,
+        None => {
+            error!("VNC server not started");
+            return 0;
+        }
+    };
+
+    let listener_id = NEXT_LISTENER_ID.fetch_add(1, Ordering::SeqCst);
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+
+    if let Some(registry) = LISTENER_REGISTRY.get() {
+        if let Ok(mut guard) = registry.lock() {
+            guard.insert(listener_id, shutdown_tx);
+        }
+    }
+
+    info!("Starting listener {} on {}:{}", listener_id, bind_addr_str, port_u16);
+
+    let runtime = get_or_init_vnc_runtime();
+    runtime.spawn(async move {
+        tokio::select! {
+            result = server.listen_addr(&bind_addr_str, port_u16) => {
+                if let Err(e) = result {
+                    error!("Listener {} error: {}", listener_id, e);
+                }
+            }
+            _ = shutdown_rx => {
+                info!("Listener {} stopped", listener_id);
+            }
+        }
+    });
+
+    listener_id as jlong
+}
+
+/// JNI entry point to stop a listener started via `vncStartListen`. Does
+/// not affect the server's primary port, other listeners, or any already
+/// accepted client sessions.
+///
+/// # Returns
+///
+/// `JNI_TRUE` if a matching listener was signaled to stop, `JNI_FALSE`
+/// otherwise.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncStopListen(
+    _env: JNIEnv,
+    _class: JClass,
+    listener_id: jlong,
+) -> jboolean {
+    let shutdown_tx = match LISTENER_REGISTRY.get().and_then(|c| c.lock().ok()).and_then(|mut g| g.remove(&(listener_id as u64))) {
+        Some(tx) => tx,
+        None => return JNI_FALSE,
+    };
+
+    if shutdown_tx.send(()).is_ok() {
+        JNI_TRUE
+    } else {
+        JNI_FALSE
+    }
+}
+
 /// JNI entry point to stop the VNC server.
 ///
 /// This function sends a shutdown signal to all active server tasks and clears the global
@@ -297,6 +567,23 @@ pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncStopS
         }
     }
 
+    // Dropping the dispatcher's sender ends its task: `rx.recv()` returns
+    // `None` once every clone is gone.
+    if let Some(dispatcher_container) = VNC_DISPATCHER.get() {
+        if let Ok(mut guard) = dispatcher_container.lock() {
+            *guard = None;
+        }
+    }
+
+    // Stop every listener started via vncStartListen along with the server.
+    if let Some(registry) = LISTENER_REGISTRY.get() {
+        if let Ok(mut guard) = registry.lock() {
+            for (_, shutdown_tx) in guard.drain() {
+                let _ = shutdown_tx.send(());
+            }
+        }
+    }
+
     // Reset event handler flag
     EVENT_HANDLER_RUNNING.store(false, Ordering::SeqCst);
 
@@ -304,6 +591,91 @@ pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncStopS
     JNI_TRUE
 }
 
+/// JNI entry point for a deadlock-safe full server shutdown.
+///
+/// Unlike `vncStopServer`, which only signals and clears, this waits (up to
+/// `SHUTDOWN_TIMEOUT`) for every still-connecting outbound reverse/repeater/
+/// WebRTC attempt to actually be aborted before the server is dropped, so a
+/// stuck dial can't outlive the `VncServer` it was connecting into. The
+/// container lock is only ever held for the instant it takes to clear a
+/// field -- never across the `block_on` below -- so this call never blocks
+/// while holding `VNC_SERVER`'s mutex.
+///
+/// # Returns
+///
+/// `JNI_TRUE` once shutdown has completed (whether or not the dispatcher
+/// replied before `SHUTDOWN_TIMEOUT` elapsed).
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncShutdown(
+    _env: JNIEnv,
+    _class: JClass,
+) -> jboolean {
+    info!("Shutting down Rust VNC Server");
+
+    // Unwind every tokio::select! waiting on this: the primary accept loop
+    // and spawn_event_handler. Listeners started via vncStartListen have
+    // their own shutdown senders, drained below.
+    if let Some(shutdown_tx) = SHUTDOWN_SIGNAL.get() {
+        let _ = shutdown_tx.send(());
+    }
+
+    // Abort every pending outbound connection attempt before dropping the
+    // server, waiting for confirmation rather than dropping the dispatcher
+    // sender and hoping its task unwinds on its own.
+    let (reply_tx, reply_rx) = oneshot::channel();
+    if send_command(VncCommand::Shutdown { reply: reply_tx }) {
+        let runtime = get_or_init_vnc_runtime();
+        if runtime.block_on(tokio::time::timeout(SHUTDOWN_TIMEOUT, reply_rx)).is_err() {
+            warn!("Timed out waiting for dispatcher shutdown, proceeding anyway");
+        }
+    }
+
+    if let Some(dispatcher_container) = VNC_DISPATCHER.get() {
+        if let Ok(mut guard) = dispatcher_container.lock() {
+            *guard = None;
+        }
+    }
+
+    if let Some(server_container) = VNC_SERVER.get() {
+        if let Ok(mut guard) = server_container.lock() {
+            *guard = None;
+        }
+    }
+
+    if let Some(registry) = LISTENER_REGISTRY.get() {
+        if let Ok(mut guard) = registry.lock() {
+            for (_, shutdown_tx) in guard.drain() {
+                let _ = shutdown_tx.send(());
+            }
+        }
+    }
+
+    EVENT_HANDLER_RUNNING.store(false, Ordering::SeqCst);
+
+    info!("Rust VNC Server shut down");
+    JNI_TRUE
+}
+
+/// JNI entry point to check whether the VNC server is currently running.
+///
+/// # Returns
+///
+/// `JNI_TRUE` if a `VncServer` instance is currently installed (started via
+/// `vncStartServer`/`vncStartServerFd` and not yet stopped), `JNI_FALSE`
+/// otherwise.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncIsRunning(
+    _env: JNIEnv,
+    _class: JClass,
+) -> jboolean {
+    match VNC_SERVER.get().and_then(|c| c.lock().ok()) {
+        Some(guard) if guard.is_some() => JNI_TRUE,
+        _ => JNI_FALSE,
+    }
+}
+
 /// JNI entry point to update the entire framebuffer with new screen data.
 ///
 /// This function receives a direct `ByteBuffer` from Java containing the new framebuffer image.
@@ -669,10 +1041,251 @@ pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncGetFr
     -1
 }
 
+/// JNI entry point to configure admission control: a maximum number of
+/// concurrent clients and an idle-disconnect timeout.
+///
+/// # Arguments
+///
+/// * `max_clients` - Maximum concurrent clients to accept, or `0` for
+///   unlimited.
+/// * `idle_timeout_secs` - Seconds a client may go without sending an RFB
+///   message before it is disconnected, or `0` to disable the idle timeout.
+///
+/// # Returns
+///
+/// `JNI_TRUE` if the policy was applied, `JNI_FALSE` if the server is not
+/// active.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncSetConnectionPolicy(
+    _env: JNIEnv,
+    _class: JClass,
+    max_clients: jint,
+    idle_timeout_secs: jint,
+) -> jboolean {
+    if max_clients < 0 || idle_timeout_secs < 0 {
+        error!("Invalid connection policy: max_clients={}, idle_timeout_secs={}", max_clients, idle_timeout_secs);
+        return JNI_FALSE;
+    }
+
+    if let Some(server_container) = VNC_SERVER.get() {
+        if let Ok(guard) = server_container.lock() {
+            if let Some(server) = guard.as_ref() {
+                server.set_connection_policy(max_clients as usize, idle_timeout_secs as u64);
+                return JNI_TRUE;
+            }
+        }
+    }
+    JNI_FALSE
+}
+
+/// JNI entry point to configure the Tight encoder used for outgoing
+/// framebuffer update rectangles.
+///
+/// # Arguments
+///
+/// * `quality_level` - 0-9 quality level, mapped internally to a JPEG
+///   quality of roughly 5-95.
+/// * `compress_level` - 0-9 zlib compression level for palette/basic
+///   sub-blocks.
+/// * `allow_jpeg` - Whether photographic rectangles may be sent as JPEG at
+///   all; when false they fall back to basic compression (PNG for Tight
+///   PNG clients).
+///
+/// # Returns
+///
+/// `JNI_TRUE` if the preferences were applied, `JNI_FALSE` if the server is
+/// not active.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncSetEncodingPreferences(
+    _env: JNIEnv,
+    _class: JClass,
+    quality_level: jint,
+    compress_level: jint,
+    allow_jpeg: jboolean,
+) -> jboolean {
+    if !(0..=9).contains(&quality_level) || !(0..=9).contains(&compress_level) {
+        error!("Invalid encoding preferences: quality_level={}, compress_level={}", quality_level, compress_level);
+        return JNI_FALSE;
+    }
+
+    if let Some(server_container) = VNC_SERVER.get() {
+        if let Ok(guard) = server_container.lock() {
+            if let Some(server) = guard.as_ref() {
+                server.set_encoding_preferences(quality_level as u8, compress_level as u8, allow_jpeg == JNI_TRUE);
+                return JNI_TRUE;
+            }
+        }
+    }
+    JNI_FALSE
+}
+
+/// JNI entry point to get the number of currently connected clients.
+///
+/// # Returns
+///
+/// The number of connected clients, or `0` if the server is not active.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncGetClientCount(
+    _env: JNIEnv,
+    _class: JClass,
+) -> jint {
+    if let Some(server_container) = VNC_SERVER.get() {
+        if let Ok(guard) = server_container.lock() {
+            if let Some(server) = guard.as_ref() {
+                let runtime = get_or_init_vnc_runtime();
+                return runtime.block_on(server.client_count()) as jint;
+            }
+        }
+    }
+    0
+}
+
+/// JNI entry point to get a human-readable summary of one client's
+/// connection: peer address and how long it has been connected.
+///
+/// # Returns
+///
+/// A `JString` like `"192.168.1.5:54321 (connected 37s)"`, or an empty
+/// string if `client_id` is not connected.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncGetClientInfo<'local>(
+    env: JNIEnv<'local>,
+    _class: JClass,
+    client_id: jlong,
+) -> JString<'local> {
+    let mut env = env;
+    let info = if let Some(server_container) = VNC_SERVER.get() {
+        server_container.lock().ok().and_then(|guard| {
+            guard.as_ref().map(|server| {
+                let runtime = get_or_init_vnc_runtime();
+                runtime.block_on(server.client_info(client_id as u64))
+            })
+        }).flatten()
+    } else {
+        None
+    };
+
+    let text = match info {
+        Some(info) => format!(
+            "{} (connected {}s{})",
+            info.peer_addr,
+            info.connected_secs,
+            if info.view_only { ", view-only" } else { "" }
+        ),
+        None => String::new(),
+    };
+
+    env.new_string(text).unwrap_or_else(|_| JString::from(JObject::null()))
+}
+
+/// JNI entry point to disconnect a single client by ID, without affecting
+/// any other connection.
+///
+/// # Returns
+///
+/// `JNI_TRUE` if a matching client was signaled to close, `JNI_FALSE`
+/// otherwise.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncDisconnectClient(
+    _env: JNIEnv,
+    _class: JClass,
+    client_id: jlong,
+) -> jboolean {
+    if let Some(server_container) = VNC_SERVER.get() {
+        if let Ok(guard) = server_container.lock() {
+            if let Some(server) = guard.as_ref() {
+                let runtime = get_or_init_vnc_runtime();
+                if runtime.block_on(server.disconnect_client(client_id as u64)) {
+                    return JNI_TRUE;
+                }
+            }
+        }
+    }
+    JNI_FALSE
+}
+
+/// JNI entry point to toggle view-only mode for a single client: its
+/// pointer/key events stop being honored, but its session and framebuffer
+/// updates continue unaffected.
+///
+/// # Returns
+///
+/// `JNI_TRUE` if a matching client was updated, `JNI_FALSE` otherwise.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncSetClientViewOnly(
+    _env: JNIEnv,
+    _class: JClass,
+    client_id: jlong,
+    view_only: jboolean,
+) -> jboolean {
+    if let Some(server_container) = VNC_SERVER.get() {
+        if let Ok(guard) = server_container.lock() {
+            if let Some(server) = guard.as_ref() {
+                let runtime = get_or_init_vnc_runtime();
+                if runtime.block_on(server.set_client_view_only(client_id as u64, view_only == JNI_TRUE)) {
+                    return JNI_TRUE;
+                }
+            }
+        }
+    }
+    JNI_FALSE
+}
+
+/// JNI entry point to demote a client to a view-only spectator or promote it
+/// back to a full controller. Unlike `vncSetClientViewOnly`, this also stops
+/// the client from driving its own `FramebufferUpdateRequest` encode path --
+/// it is instead relayed the controller's frames, following the same
+/// control/spectator split new connections get at connect time.
+///
+/// # Returns
+///
+/// `JNI_TRUE` if a matching client was updated, `JNI_FALSE` otherwise.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncSetClientAccess(
+    _env: JNIEnv,
+    _class: JClass,
+    client_id: jlong,
+    view_only: jboolean,
+) -> jboolean {
+    if let Some(server_container) = VNC_SERVER.get() {
+        if let Ok(guard) = server_container.lock() {
+            if let Some(server) = guard.as_ref() {
+                let runtime = get_or_init_vnc_runtime();
+                if runtime.block_on(server.set_client_access(client_id as u64, view_only == JNI_TRUE)) {
+                    return JNI_TRUE;
+                }
+            }
+        }
+    }
+    JNI_FALSE
+}
+
+/// Pushes `command` onto the dispatcher's queue, returning `false` if no
+/// dispatcher is currently running (server not started).
+fn send_command(command: VncCommand) -> bool {
+    match VNC_DISPATCHER.get().and_then(|c| c.lock().ok()).and_then(|g| g.clone()) {
+        Some(tx) => tx.send(command).is_ok(),
+        None => false,
+    }
+}
+
 /// JNI entry point to initiate a direct reverse VNC connection to a viewer.
 ///
-/// This function establishes a direct connection to a VNC viewer without using
-/// a repeater. The function blocks until the connection attempt succeeds or fails.
+/// This function establishes a direct connection to a VNC viewer without
+/// using a repeater. Rather than locking the server and dialing out
+/// in-line, it pushes a `VncCommand::ConnectReverse` onto the dispatcher's
+/// queue and only blocks on that command's own `oneshot` reply, so the
+/// `VNC_SERVER` lock is never held while dialing. The dispatcher reserves
+/// the client ID before dialing begins, but this call blocks until the
+/// attempt has succeeded or failed, so the ID it returns no longer names a
+/// pending attempt: `vncCancelPendingConnection` with it is a no-op.
 ///
 /// # Arguments
 ///
@@ -700,55 +1313,46 @@ pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncConne
         }
     };
 
-    let port_u16 = port as u16;
+    let port_u16 = match u16::try_from(port) {
+        Ok(p) if p > 0 => p,
+        _ => {
+            error!("Invalid reverse connection port: {}", port);
+            return 0;
+        }
+    };
 
     info!(
         "Initiating reverse connection to {}:{}",
         host_str, port_u16
     );
 
-    if let Some(server_container) = VNC_SERVER.get() {
-        let server = match server_container.lock() {
-            Ok(guard) => {
-                if let Some(s) = guard.as_ref() {
-                    s.clone()
-                } else {
-                    error!("VNC server not started");
-                    return 0;
-                }
-            }
-            Err(e) => {
-                error!("Failed to lock server container: {}", e);
-                return 0;
-            }
-        };
-
-        let runtime = get_or_init_vnc_runtime();
-
-        // Block until connection succeeds or fails
-        let result = runtime.block_on(async move {
-            match server.connect_reverse(host_str, port_u16).await {
-                Ok(client_id) => {
-                    info!("Reverse connection established, client ID: {}", client_id);
-                    client_id as jlong
-                }
-                Err(e) => {
-                    error!("Failed to establish reverse connection: {}", e);
-                    0
-                }
-            }
-        });
-
-        return result;
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    if !send_command(VncCommand::ConnectReverse { host: host_str, port: port_u16, reply: reply_tx }) {
+        error!("VNC server not started");
+        return 0;
     }
 
-    error!("VNC server not initialized");
-    0
+    let runtime = get_or_init_vnc_runtime();
+    match runtime.block_on(reply_rx) {
+        Ok(Ok(client_id)) => {
+            info!("Reverse connection established, client ID: {}", client_id);
+            client_id as jlong
+        }
+        Ok(Err(e)) => {
+            error!("Failed to establish reverse connection: {}", e);
+            0
+        }
+        Err(_) => {
+            error!("Dispatcher dropped reverse connection reply");
+            0
+        }
+    }
 }
 
 /// JNI entry point to connect to a VNC repeater for a reverse connection.
 ///
-/// This function blocks until the connection attempt succeeds or fails.
+/// Routed through the dispatcher exactly like `vncConnectReverse`; see its
+/// doc comment for the rationale.
 ///
 /// # Arguments
 ///
@@ -786,50 +1390,140 @@ pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncConne
         }
     };
 
-    let port_u16 = port as u16;
+    let port_u16 = match u16::try_from(port) {
+        Ok(p) if p > 0 => p,
+        _ => {
+            error!("Invalid repeater port: {}", port);
+            return 0;
+        }
+    };
 
     info!(
         "Connecting to VNC repeater {}:{} with ID: {}",
         host_str, port_u16, repeater_id_str
     );
 
-    if let Some(server_container) = VNC_SERVER.get() {
-        let server = match server_container.lock() {
-            Ok(guard) => {
-                if let Some(s) = guard.as_ref() {
-                    s.clone()
-                } else {
-                    error!("VNC server not started");
-                    return 0;
-                }
-            }
-            Err(e) => {
-                error!("Failed to lock server container: {}", e);
-                return 0;
-            }
-        };
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    if !send_command(VncCommand::ConnectRepeater {
+        host: host_str,
+        port: port_u16,
+        repeater_id: repeater_id_str,
+        reply: reply_tx,
+    }) {
+        error!("VNC server not started");
+        return 0;
+    }
 
-        let runtime = get_or_init_vnc_runtime();
+    let runtime = get_or_init_vnc_runtime();
+    match runtime.block_on(reply_rx) {
+        Ok(Ok(client_id)) => {
+            info!("Repeater connection established, client ID: {}", client_id);
+            client_id as jlong
+        }
+        Ok(Err(e)) => {
+            error!("Failed to connect to repeater: {}", e);
+            0
+        }
+        Err(_) => {
+            error!("Dispatcher dropped repeater connection reply");
+            0
+        }
+    }
+}
 
-        // Block until connection succeeds or fails
-        let result = runtime.block_on(async move {
-            match server.connect_repeater(host_str, port_u16, repeater_id_str).await {
-                Ok(client_id) => {
-                    info!("Repeater connection established, client ID: {}", client_id);
-                    client_id as jlong
-                }
-                Err(e) => {
-                    error!("Failed to connect to repeater: {}", e);
-                    0
-                }
-            }
-        });
+/// JNI entry point to establish a WebRTC data-channel connection from an
+/// SDP offer, for viewers and servers that can't reach each other through a
+/// direct socket or a repeater. Routed through the dispatcher like
+/// `vncConnectReverse`; the returned client ID is the one `onClientConnected`
+/// and the other per-client JNI calls will later use.
+///
+/// Following the idiom other Android JNI bridges use for WebRTC (passing
+/// SDP strings as plain `String`s and returning an out-parameter via a
+/// mutable object since JNI functions return a single scalar), the answer
+/// SDP -- which the caller must still relay back over its own signaling
+/// channel -- is appended to `answer_sdp_out`, a Java `StringBuilder`.
+///
+/// # Arguments
+///
+/// * `env` - The JNI environment.
+/// * `_class` - The Java class from which this method is called.
+/// * `offer_sdp` - The remote peer's SDP offer.
+/// * `answer_sdp_out` - A `java.lang.StringBuilder` that the local SDP
+///   answer is appended to on success.
+///
+/// # Returns
+///
+/// The new client ID (`jlong`) if the offer/answer exchange and data
+/// channel setup succeeded, or `0` on failure.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncConnectWebRTC(
+    mut env: JNIEnv,
+    _class: JClass,
+    offer_sdp: JString,
+    answer_sdp_out: JObject,
+) -> jlong {
+    let offer_sdp_str: String = match env.get_string(&offer_sdp) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            error!("Failed to get WebRTC SDP offer: {}", e);
+            return 0;
+        }
+    };
+
+    info!("Accepting WebRTC offer ({} bytes of SDP)", offer_sdp_str.len());
 
-        return result;
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    if !send_command(VncCommand::ConnectWebRtc { offer_sdp: offer_sdp_str, reply: reply_tx }) {
+        error!("VNC server not started");
+        return 0;
     }
 
-    error!("VNC server not initialized");
-    0
+    let runtime = get_or_init_vnc_runtime();
+    let (client_id, answer_sdp) = match runtime.block_on(reply_rx) {
+        Ok(Ok(pair)) => pair,
+        Ok(Err(e)) => {
+            error!("Failed to establish WebRTC connection: {}", e);
+            return 0;
+        }
+        Err(_) => {
+            error!("Dispatcher dropped WebRTC connection reply");
+            return 0;
+        }
+    };
+
+    if let Ok(jsdp) = env.new_string(&answer_sdp) {
+        let args = [JValue::Object(&jsdp)];
+        if let Err(e) = env.call_method(&answer_sdp_out, "append", "(Ljava/lang/String;)Ljava/lang/StringBuilder;", &args) {
+            error!("Failed to append WebRTC SDP answer: {}", e);
+        }
+    }
+
+    info!("WebRTC connection established, client ID: {}", client_id);
+    client_id as jlong
+}
+
+/// JNI entry point to cancel a reverse/repeater connection attempt that was
+/// dispatched but has not yet finished dialing. A no-op if `client_id`
+/// already finished connecting (it's a live session at that point, not a
+/// pending one -- use `vncDisconnectClient` instead) or was never issued.
+///
+/// # Returns
+///
+/// `JNI_TRUE` if the cancellation was queued, `JNI_FALSE` if no dispatcher
+/// is running.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_net_christianbeier_droidvnc_1ng_MainService_vncCancelPendingConnection(
+    _env: JNIEnv,
+    _class: JClass,
+    client_id: jlong,
+) -> jboolean {
+    if send_command(VncCommand::CancelConnection { client_id: client_id as u64 }) {
+        JNI_TRUE
+    } else {
+        JNI_FALSE
+    }
 }
 
 /// Spawns a long-running asynchronous task to handle VNC server events.
@@ -874,6 +1568,30 @@ fn spawn_event_handler(mut event_rx: mpsc::UnboundedReceiver<ServerEvent>) {
     });
 }
 
+/// Spawns the command dispatcher for a freshly created `VncServer` and
+/// stores its sender, replacing whatever sender was left over from a
+/// previous run.
+///
+/// # Arguments
+///
+/// * `server` - The `VncServer` the dispatcher will issue connection
+///   commands against.
+fn spawn_dispatcher(server: Arc<VncServer>) {
+    let runtime = get_or_init_vnc_runtime();
+    // `dispatcher::spawn` calls `tokio::spawn` directly, so it needs an
+    // active runtime context even though it isn't itself async.
+    let tx = {
+        let _guard = runtime.enter();
+        dispatcher::spawn(server)
+    };
+
+    if let Some(dispatcher_container) = VNC_DISPATCHER.get() {
+        if let Ok(mut guard) = dispatcher_container.lock() {
+            *guard = Some(tx);
+        }
+    }
+}
+
 /// Handles a single `ServerEvent`, calling the appropriate Java static method.
 ///
 /// This function attaches the current thread to the Java VM, determines the type of
@@ -901,15 +1619,15 @@ fn handle_server_event(event: ServerEvent) {
     };
 
     match event {
-        ServerEvent::ClientConnected { client_id } => {
-            info!("Client {} connected", client_id);
+        ServerEvent::ClientConnected { client_id, spectator } => {
+            info!("Client {} connected (spectator: {})", client_id, spectator);
             if let Some(main_class) = MAIN_SERVICE_CLASS.get() {
-                let args = [JValue::Long(client_id as jlong)];
+                let args = [JValue::Long(client_id as jlong), JValue::Bool(spectator as jboolean)];
                 // Log JNI errors to aid debugging
                 if let Err(e) = env.call_static_method(
                     main_class,
                     "onClientConnected",
-                    "(J)V",
+                    "(JZ)V",
                     &args,
                 ) {
                     error!("Failed to call onClientConnected: {}", e);
@@ -992,5 +1710,79 @@ fn handle_server_event(event: ServerEvent) {
                 }
             }
         }
+        ServerEvent::PasswordRequest { client_id, reply } => {
+            let password = call_credential_method(&mut env, "onPasswordRequest", client_id, None);
+            let _ = reply.send(password);
+        }
+        ServerEvent::CredentialRequest { client_id, kind, reply } => {
+            let credential = call_credential_method(&mut env, "onCredentialRequest", client_id, Some(&kind));
+            let _ = reply.send(credential);
+        }
+        ServerEvent::WebRtcStateChanged { client_id, state } => {
+            if let Some(main_class) = MAIN_SERVICE_CLASS.get() {
+                if let Ok(jstate) = env.new_string(&state) {
+                    let args = [
+                        JValue::Object(&jstate),
+                        JValue::Long(client_id as jlong),
+                    ];
+                    if let Err(e) = env.call_static_method(
+                        main_class,
+                        "onWebRTCState",
+                        "(Ljava/lang/String;J)V",
+                        &args,
+                    ) {
+                        error!("Failed to call onWebRTCState: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Calls a Java credential callback (`onPasswordRequest`/
+/// `onCredentialRequest`) and returns what it answers with, or an empty
+/// string if the call fails for any reason, so the handshake's `reply` is
+/// always answered rather than left to time out.
+///
+/// `kind` is `None` for `onPasswordRequest` (signature `(J)Ljava/lang/String;`)
+/// and `Some` for `onCredentialRequest` (signature
+/// `(Ljava/lang/String;J)Ljava/lang/String;`).
+fn call_credential_method(env: &mut JNIEnv, method: &str, client_id: u64, kind: Option<&str>) -> String {
+    let main_class = match MAIN_SERVICE_CLASS.get() {
+        Some(c) => c,
+        None => return String::new(),
+    };
+
+    let result = match kind {
+        None => env.call_static_method(
+            main_class,
+            method,
+            "(J)Ljava/lang/String;",
+            &[JValue::Long(client_id as jlong)],
+        ),
+        Some(kind) => match env.new_string(kind) {
+            Ok(jkind) => env.call_static_method(
+                main_class,
+                method,
+                "(Ljava/lang/String;J)Ljava/lang/String;",
+                &[JValue::Object(&jkind), JValue::Long(client_id as jlong)],
+            ),
+            Err(e) => {
+                error!("Failed to build credential kind string: {}", e);
+                return String::new();
+            }
+        },
+    };
+
+    match result.and_then(|v| v.l()) {
+        Ok(obj) if !obj.is_null() => {
+            let jstring = JString::from(obj);
+            env.get_string(&jstring).map(|s| s.into()).unwrap_or_default()
+        }
+        Ok(_) => String::new(),
+        Err(e) => {
+            error!("Failed to call {}: {}", method, e);
+            String::new()
+        }
     }
 }